@@ -0,0 +1,177 @@
+//! # I/O APIC
+//! The I/O APIC routes external device interrupts (legacy ISA IRQs such as the
+//! keyboard and the PIT timer fallback) to local APICs. This driver parses the
+//! IOAPIC and Interrupt Source Override entries from the MADT, maps each
+//! IOAPIC's MMIO window, and programs its redirection table so that a handler
+//! registered for an ISA IRQ line is delivered to the BSP.
+
+use core::ptr::{read_volatile, write_volatile};
+
+use spin::mutex::spin::SpinMutex;
+
+use crate::acpi::madt::Madt;
+use crate::logln;
+use crate::memory::vmm;
+
+/// MMIO offset of the register-select window.
+const IOREGSEL: usize = 0x00;
+/// MMIO offset of the register data window.
+const IOWIN: usize = 0x10;
+/// Index of the first redirection-table register; entry `n` lives at
+/// `IOREDTBL + 2*n` (low dword) and `IOREDTBL + 2*n + 1` (high dword).
+const IOREDTBL: u32 = 0x10;
+
+/// A redirection-table entry is masked while this bit is set.
+const REDIR_MASK: u64 = 1 << 16;
+/// Active-low polarity bit.
+const REDIR_ACTIVE_LOW: u64 = 1 << 13;
+/// Level-triggered mode bit.
+const REDIR_LEVEL_TRIGGERED: u64 = 1 << 15;
+
+/// The system's I/O APICs, populated from the MADT during interrupt init.
+pub static IO_APICS: SpinMutex<IoApicSet> = SpinMutex::new(IoApicSet::new());
+
+/// A single I/O APIC and the range of global system interrupts it owns.
+pub struct IoApic {
+    base: *mut u32,
+    gsi_base: u32,
+    count: u32,
+}
+
+// The MMIO window is only touched under the `IO_APICS` lock.
+unsafe impl Send for IoApic {}
+
+/// The set of I/O APICs plus the ISA-IRQ source overrides from the MADT.
+pub struct IoApicSet {
+    apics: [Option<IoApic>; Self::MAX],
+    len: usize,
+    overrides: [SourceOverride; 16],
+}
+
+/// An Interrupt Source Override: remaps an ISA IRQ onto a GSI and records its
+/// polarity/trigger flags.
+#[derive(Clone, Copy)]
+struct SourceOverride {
+    gsi: u32,
+    flags: u16,
+}
+
+impl IoApicSet {
+    const MAX: usize = 8;
+
+    const fn new() -> Self {
+        // By default ISA IRQ `n` maps identically onto GSI `n`.
+        let mut overrides = [SourceOverride { gsi: 0, flags: 0 }; 16];
+        let mut irq = 0;
+        while irq < 16 {
+            overrides[irq] = SourceOverride {
+                gsi: irq as u32,
+                flags: 0,
+            };
+            irq += 1;
+        }
+        IoApicSet {
+            apics: [const { None }; Self::MAX],
+            len: 0,
+            overrides,
+        }
+    }
+
+    /// Parse the IOAPIC and source-override entries from the MADT and map each
+    /// controller's registers. `paddr_width` bounds the physical MMIO base.
+    pub fn init(&mut self, madt: &Madt, paddr_width: u8) {
+        let paddr_mask = (1u64 << paddr_width) - 1;
+        for entry in madt.io_apics() {
+            // The register window is remapped into the high-half MMIO region
+            // with caching disabled rather than assumed identity-mapped.
+            let paddr = (entry.address as u64 & paddr_mask) as usize;
+            let base = vmm::map_mmio(paddr, 0x20) as *mut u32;
+            let mut apic = IoApic {
+                base,
+                gsi_base: entry.gsi_base,
+                count: 0,
+            };
+            apic.count = apic.max_redirection_entry() + 1;
+            logln!(
+                "I/O APIC at {:#x} owns GSIs {}..{}",
+                base as usize,
+                apic.gsi_base,
+                apic.gsi_base + apic.count
+            );
+            if self.len < Self::MAX {
+                self.apics[self.len] = Some(apic);
+                self.len += 1;
+            }
+        }
+        for so in madt.source_overrides() {
+            if (so.source as usize) < self.overrides.len() {
+                self.overrides[so.source as usize] = SourceOverride {
+                    gsi: so.gsi,
+                    flags: so.flags,
+                };
+            }
+        }
+    }
+
+    /// Route an ISA IRQ line to `vector` on `dest_apic_id`, resolving the GSI
+    /// through the source overrides and unmasking the redirection entry.
+    pub fn route_isa_irq(&mut self, irq: u8, vector: u8, dest_apic_id: u8) {
+        let ovr = self.overrides[irq as usize];
+        let gsi = ovr.gsi;
+        let apic = match self.owner_of(gsi) {
+            Some(apic) => apic,
+            None => {
+                logln!("No I/O APIC owns GSI {} for ISA IRQ {}", gsi, irq);
+                return;
+            }
+        };
+        let index = gsi - apic.gsi_base;
+
+        let mut entry = vector as u64;
+        // MPS INTI flags: bits 0-1 encode polarity and bits 2-3 trigger mode;
+        // 0b11 selects active-low / level-triggered, anything else keeps the
+        // ISA default (active-high, edge).
+        if ovr.flags & 0b11 == 0b11 {
+            entry |= REDIR_ACTIVE_LOW;
+        }
+        if (ovr.flags >> 2) & 0b11 == 0b11 {
+            entry |= REDIR_LEVEL_TRIGGERED;
+        }
+        entry |= (dest_apic_id as u64) << 56;
+
+        unsafe { apic.write_redirection(index, entry) };
+    }
+
+    fn owner_of(&mut self, gsi: u32) -> Option<&mut IoApic> {
+        self.apics[..self.len]
+            .iter_mut()
+            .filter_map(|a| a.as_mut())
+            .find(|a| gsi >= a.gsi_base && gsi < a.gsi_base + a.count)
+    }
+}
+
+impl IoApic {
+    unsafe fn read(&self, reg: u32) -> u32 {
+        write_volatile(self.base.byte_add(IOREGSEL), reg);
+        read_volatile(self.base.byte_add(IOWIN))
+    }
+
+    unsafe fn write(&self, reg: u32, value: u32) {
+        write_volatile(self.base.byte_add(IOREGSEL), reg);
+        write_volatile(self.base.byte_add(IOWIN), value);
+    }
+
+    /// Highest redirection-entry index (bits 16..23 of the IOAPICVER register).
+    fn max_redirection_entry(&self) -> u32 {
+        (unsafe { self.read(0x01) } >> 16) & 0xff
+    }
+
+    /// Write a 64-bit redirection entry, masking it before the low dword so the
+    /// line cannot fire with a half-programmed destination.
+    unsafe fn write_redirection(&self, index: u32, entry: u64) {
+        let reg = IOREDTBL + 2 * index;
+        self.write(reg, (self.read(reg) | REDIR_MASK as u32) & 0xffff_ffff);
+        self.write(reg + 1, (entry >> 32) as u32);
+        self.write(reg, entry as u32);
+    }
+}
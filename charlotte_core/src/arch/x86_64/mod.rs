@@ -2,6 +2,7 @@
 //! This module implements the Arch interface for the x86_64 instruction set architecture (ISA).
 
 use core::fmt::Write;
+use core::panic::PanicInfo;
 use core::str;
 use core::{
     borrow::{Borrow, BorrowMut},
@@ -32,6 +33,8 @@ mod global;
 mod idt;
 mod interrupts;
 mod serial;
+mod smp;
+mod testing;
 
 /// The Api struct is used to provide an implementation of the ArchApi trait for the x86_64 architecture.
 pub struct Api {
@@ -45,6 +48,9 @@ static BSP_TSS: Lazy<Tss> = Lazy::new(|| Tss::new(addr_of!(BSP_RING0_INT_STACK)
 static BSP_GDT: Lazy<Gdt> = Lazy::new(|| Gdt::new(&BSP_TSS));
 static BSP_IDT: SpinMutex<Idt> = SpinMutex::new(Idt::new());
 
+/// First interrupt vector used for legacy ISA IRQ lines (IRQ 0 -> vector 0x20).
+const ISA_IRQ_VECTOR_BASE: u8 = 0x20;
+
 pub const X86_ISA_PARAMS: IsaParams = IsaParams {
     paging: PagingParams {
         page_size: 0x1000,
@@ -82,13 +88,33 @@ impl crate::arch::Api for Api {
         logln!("============================================================\n");
 
         logln!("Memory self test");
-        Self::pmm_self_test();
-        logln!("============================================================\n");
 
-        logln!("All x86_64 sanity checks passed, kernel main has control now");
-        logln!("============================================================\n");
+        // Under a test build, run the structured suite instead of the ad-hoc
+        // checks: it reports pass/fail over serial and terminates the VM with a
+        // CI-observable exit code, so it never returns and the normal boot tail
+        // below is compiled out.
+        #[cfg(feature = "run_tests")]
+        {
+            Self::run_self_tests()
+        }
+
+        #[cfg(not(feature = "run_tests"))]
+        {
+            Self::pmm_self_test();
+            // Bring the global kernel heap up right after the PMM self test so the
+            // rest of boot can rely on dynamic allocation.
+            crate::memory::heap::init_heap();
+            logln!("============================================================\n");
+
+            logln!("Bringing up application processors");
+            api.boot_aps();
+            logln!("============================================================\n");
+
+            logln!("All x86_64 sanity checks passed, kernel main has control now");
+            logln!("============================================================\n");
 
-        api
+            api
+        }
     }
 
     /// Get a new logger instance
@@ -115,8 +141,46 @@ impl crate::arch::Api for Api {
     }
 
     /// Kernel Panic
-    fn panic() -> ! {
-        unsafe { asm_halt() }
+    ///
+    /// Emit a best-effort crash report over COM1 — message, location, a
+    /// frame-pointer backtrace, the significant address widths, and the
+    /// current APIC ID so multi-core faults are attributable — then halt with
+    /// interrupts disabled.
+    fn panic(info: &PanicInfo) -> ! {
+        irq_disable();
+        // Acquire a fresh COM1 port rather than an existing lock, which may be
+        // poisoned or held by the faulting core.
+        if let Ok(mut serial) = SerialPort::try_new(ComPort::COM1) {
+            let _ = writeln!(serial, "\n=================== KERNEL PANIC ===================");
+            if let Some(location) = info.location() {
+                let _ = writeln!(
+                    serial,
+                    "at {}:{}:{}",
+                    location.file(),
+                    location.line(),
+                    location.column()
+                );
+            }
+            let _ = writeln!(serial, "message: {}", info.message());
+            let _ = writeln!(
+                serial,
+                "cpu: APIC ID {} | paddr bits {} | vaddr bits {}",
+                current_apic_id(),
+                *PADDR_SIG_BITS,
+                *VADDR_SIG_BITS
+            );
+            backtrace(&mut serial);
+            let _ = writeln!(serial, "===================================================");
+        }
+        // Under a test build a panic means a failed test case: terminate the VM
+        // through the QEMU debug-exit device so CI observes the distinct failure
+        // code rather than hanging on a silent halt.
+        #[cfg(feature = "run_tests")]
+        testing::exit_qemu(testing::ExitCode::Failed);
+        #[cfg(not(feature = "run_tests"))]
+        unsafe {
+            asm_halt()
+        }
     }
 
     /// Read a byte from the specified port
@@ -134,6 +198,9 @@ impl crate::arch::Api for Api {
     ///  Initialize the application processors (APs)
     fn init_ap(&mut self) {
         //! This routine is run by each application processor to initialize itself prior to being handed off to the scheduler.
+        //! It performs the per-core finalization that `smp::ap_entry` calls back into once the core is in long mode with
+        //! its per-CPU GDT/TSS and `BSP_IDT` loaded: enable this core's local APIC. `ap_entry` parks the core afterwards.
+        self.bsp_apic.enable(BSP_IDT.lock().borrow_mut());
     }
 
     fn setup_isa_timer(&mut self, tps: u32, mode: HwTimerMode, _: u16) {
@@ -177,6 +244,9 @@ impl crate::arch::Api for Api {
 
     fn init_interrupts(&mut self) {
         self.bsp_apic.enable(BSP_IDT.lock().borrow_mut());
+        interrupts::ioapic::IO_APICS
+            .lock()
+            .init(self.acpi_info.madt(), Api::get_paddr_width());
     }
 
     fn set_interrupt_handler(&mut self, h: fn(vector: u64), vector: u32) {
@@ -184,6 +254,15 @@ impl crate::arch::Api for Api {
             panic!("X86_64 can only have from iv 32 to iv 255 set");
         }
         register_iv_handler(h, vector as u8);
+        // Vectors in the legacy ISA range are backed by an external device line:
+        // resolve the GSI through the source overrides and unmask the matching
+        // I/O APIC redirection entry, targeting the BSP.
+        if (ISA_IRQ_VECTOR_BASE..ISA_IRQ_VECTOR_BASE + 16).contains(&(vector as u8)) {
+            let irq = vector as u8 - ISA_IRQ_VECTOR_BASE;
+            interrupts::ioapic::IO_APICS
+                .lock()
+                .route_isa_irq(irq, vector as u8, self.bsp_apic.id());
+        }
     }
 
     #[inline(always)]
@@ -202,6 +281,29 @@ impl Api {
         *VADDR_SIG_BITS
     }
 
+    /// Bring up every application processor described by the MADT.
+    ///
+    /// Enumerates the processor-local-APIC entries, drops the BSP's own APIC ID,
+    /// and hands the remaining target IDs to the SMP INIT–SIPI–SIPI driver.
+    fn boot_aps(&mut self) {
+        let bsp_id = self.bsp_apic.id();
+        // The MADT can describe at most 255 local APICs (8-bit IDs).
+        let mut targets = [0u8; u8::MAX as usize];
+        let mut count = 0usize;
+        for id in self.acpi_info.madt().application_processor_apic_ids() {
+            if id != bsp_id {
+                targets[count] = id;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            logln!("No application processors to bring up");
+            return;
+        }
+        smp::boot_aps(self, &targets[..count]);
+        logln!("{} application processor(s) online", count);
+    }
+
     fn init_bsp() {
         //! This routine is run by the bootstrap processor to initialize itself prior to bringing up the kernel.
         logln!("Processor information:");
@@ -236,43 +338,135 @@ impl Api {
         );
 
         logln!("Testing Physical Memory Manager");
-        logln!("Performing single frame allocation and deallocation test.");
-        let alloc = PHYSICAL_FRAME_ALLOCATOR.lock().allocate();
-        let alloc2 = PHYSICAL_FRAME_ALLOCATOR.lock().allocate();
-        match alloc {
-            Ok(frame) => {
-                logln!("Allocated frame with physical base address: {:?}", frame);
-                let _ = PHYSICAL_FRAME_ALLOCATOR.lock().deallocate(frame);
-                logln!("Deallocated frame with physical base address: {:?}", frame);
-            }
-            Err(e) => {
-                logln!("Failed to allocate frame: {:?}", e);
-            }
+        test_single_frame_alloc();
+        test_contiguous_frame_alloc();
+        test_kernel_heap();
+        logln!("Physical Memory Manager test suite finished.");
+    }
+
+    /// Run the structured self-test suite and terminate the VM with a
+    /// CI-observable exit code. Invoked from `isa_init` under a test build.
+    #[cfg(feature = "run_tests")]
+    fn run_self_tests() -> ! {
+        use testing::NamedTest;
+
+        const TESTS: &[&dyn testing::TestCase] = &[
+            &NamedTest {
+                name: "single_frame_alloc",
+                test: test_single_frame_alloc,
+            },
+            &NamedTest {
+                name: "contiguous_frame_alloc",
+                test: test_contiguous_frame_alloc,
+            },
+            &NamedTest {
+                name: "kernel_heap",
+                test: test_kernel_heap,
+            },
+        ];
+        testing::run_tests(TESTS)
+    }
+}
+
+/// Read the current core's local APIC ID from CPUID leaf 1 (EBX bits 24..31),
+/// which is lock-free and safe to call from the panic path.
+fn current_apic_id() -> u8 {
+    let ebx: u32;
+    unsafe {
+        core::arch::asm!(
+            "push rbx",
+            "cpuid",
+            "mov {ebx:e}, ebx",
+            "pop rbx",
+            ebx = out(reg) ebx,
+            inout("eax") 1u32 => _,
+            out("ecx") _,
+            out("edx") _,
+        );
+    }
+    (ebx >> 24) as u8
+}
+
+/// Walk saved RBP frames, printing each return address, until a null or
+/// non-canonical frame pointer is reached or a sane bound is exceeded.
+fn backtrace(serial: &mut SerialPort) {
+    let mut rbp: u64;
+    unsafe { core::arch::asm!("mov {}, rbp", out(reg) rbp) };
+
+    let _ = writeln!(serial, "backtrace:");
+    for _ in 0..64 {
+        if rbp == 0 || !is_canonical(rbp) || rbp & 0x7 != 0 {
+            break;
         }
-        let alloc3 = PHYSICAL_FRAME_ALLOCATOR.lock().allocate();
-        logln!("alloc2: {:?}, alloc3: {:?}", alloc2, alloc3);
-        let _ = PHYSICAL_FRAME_ALLOCATOR.lock().deallocate(alloc2.unwrap());
-        let _ = PHYSICAL_FRAME_ALLOCATOR.lock().deallocate(alloc3.unwrap());
-        logln!("Single frame allocation and deallocation test complete.");
-        logln!("Performing contiguous frame allocation and deallocation test.");
-        let contiguous_alloc = PHYSICAL_FRAME_ALLOCATOR.lock().allocate_contiguous(256, 64);
-        match contiguous_alloc {
-            Ok(frame) => {
-                logln!(
-                    "Allocated physically contiguous region with physical base address: {:?}",
-                    frame
-                );
-                let _ = PHYSICAL_FRAME_ALLOCATOR.lock().deallocate(frame);
-                logln!(
-                    "Deallocated physically contiguous region with physical base address: {:?}",
-                    frame
-                );
-            }
-            Err(e) => {
-                logln!("Failed to allocate contiguous frames: {:?}", e);
-            }
+        let frame = rbp as *const u64;
+        let saved_rbp = unsafe { *frame };
+        let return_addr = unsafe { *frame.add(1) };
+        if return_addr == 0 {
+            break;
         }
-        logln!("Contiguous frame allocation and deallocation test complete.");
-        logln!("Physical Memory Manager test suite finished.");
+        let _ = writeln!(serial, "  {:#018x}", return_addr);
+        rbp = saved_rbp;
+    }
+}
+
+/// A 64-bit address is canonical when bits 47..63 are all equal to bit 47.
+fn is_canonical(addr: u64) -> bool {
+    let top = addr >> 47;
+    top == 0 || top == 0x1ffff
+}
+
+/// Single frame allocation and deallocation.
+fn test_single_frame_alloc() {
+    logln!("Performing single frame allocation and deallocation test.");
+    let frame = PHYSICAL_FRAME_ALLOCATOR
+        .lock()
+        .allocate()
+        .expect("single frame allocation failed");
+    logln!("Allocated frame with physical base address: {:?}", frame);
+    PHYSICAL_FRAME_ALLOCATOR
+        .lock()
+        .deallocate(frame)
+        .expect("single frame deallocation failed");
+    logln!("Single frame allocation and deallocation test complete.");
+}
+
+/// Physically contiguous, aligned multi-frame allocation and deallocation.
+fn test_contiguous_frame_alloc() {
+    logln!("Performing contiguous frame allocation and deallocation test.");
+    let region = PHYSICAL_FRAME_ALLOCATOR
+        .lock()
+        .allocate_contiguous(256, 64)
+        .expect("contiguous frame allocation failed");
+    logln!(
+        "Allocated physically contiguous region with physical base address: {:?}",
+        region
+    );
+    PHYSICAL_FRAME_ALLOCATOR
+        .lock()
+        .deallocate(region)
+        .expect("contiguous frame deallocation failed");
+    logln!("Contiguous frame allocation and deallocation test complete.");
+}
+
+/// Boxed value and a growing vector round-tripped through the global heap.
+fn test_kernel_heap() {
+    use alloc::boxed::Box;
+    use alloc::vec::Vec;
+
+    logln!("Performing kernel heap sanity check.");
+    crate::memory::heap::init_heap();
+
+    let boxed = Box::new(0xC0FFEEu32);
+    assert_eq!(*boxed, 0xC0FFEE);
+    logln!("Allocated boxed value: {:#x}", *boxed);
+    drop(boxed);
+
+    let mut v = Vec::new();
+    for i in 0..1024u32 {
+        v.push(i);
     }
+    assert_eq!(v.len(), 1024);
+    assert_eq!(v[1023], 1023);
+    logln!("Grew a vector to {} elements and freed it", v.len());
+    logln!("Kernel heap sanity check complete.");
 }
@@ -0,0 +1,227 @@
+//! # Symmetric Multiprocessing Bringup
+//! This module brings the application processors (APs) online using the
+//! APIC INIT–SIPI–SIPI sequence driven off the processor-local-APIC entries
+//! in the MADT. The BSP stages a real-mode trampoline in low memory, points
+//! each AP at a freshly allocated ring-0 stack, a per-CPU GDT/TSS and the
+//! shared `BSP_IDT`, and spins on a per-core acknowledgement until the AP
+//! reports in.
+
+use alloc::boxed::Box;
+use core::arch::global_asm;
+use core::ptr::{addr_of, write_volatile};
+use core::sync::atomic::{AtomicPtr, AtomicUsize, Ordering};
+
+use gdt::tss::Tss;
+
+use crate::arch::x86_64::gdt::{self, Gdt};
+use crate::arch::x86_64::idt::Idt;
+use crate::arch::x86_64::{Api, BSP_IDT};
+use crate::logln;
+use crate::memory::pmm::PHYSICAL_FRAME_ALLOCATOR;
+
+global_asm!(include_str!("trampoline.s"));
+
+extern "C" {
+    static trampoline_start: u8;
+    static trampoline_end: u8;
+}
+
+/// IA32_APIC_BASE MSR; bits 12.. hold the local APIC's physical base.
+const IA32_APIC_BASE: u32 = 0x1B;
+/// Interrupt Command Register, low dword (xAPIC MMIO offset).
+const ICR_LOW: usize = 0x300;
+/// Interrupt Command Register, high dword (holds the destination field).
+const ICR_HIGH: usize = 0x310;
+/// Delivery-status bit in the ICR; set while an IPI is still in flight.
+const ICR_DELIVERY_PENDING: u32 = 1 << 12;
+
+/// Number of APs that have finished per-core init and parked. Incremented by
+/// each AP from `ap_entry`; the BSP waits on it after every SIPI.
+static AP_ONLINE: AtomicUsize = AtomicUsize::new(0);
+
+/// The BSP's `Api`, published so the AP's `ap_entry` can call back into
+/// `Api::init_ap` for its per-core finalization. Valid only for the duration of
+/// `boot_aps`, which brings APs up one at a time and blocks on each.
+static BSP_API: AtomicPtr<Api> = AtomicPtr::new(core::ptr::null_mut());
+
+/// The per-CPU `Gdt` (owning a fresh TSS and ring-0 interrupt stack) staged for
+/// the AP currently being brought up. The AP loads it from `ap_entry`.
+static AP_GDT: AtomicPtr<Gdt> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Arguments the BSP patches into the trampoline tail before the SIPIs. The
+/// field order mirrors the `trampoline_*` cells at the end of `trampoline.s`.
+#[repr(C)]
+struct ApTrampolineArgs {
+    pml4: u64,
+    stack_top: u64,
+    entry: u64,
+}
+
+/// Bring every application processor described by the MADT online.
+///
+/// `apic_ids` is the set of processor-local-APIC identifiers enumerated from
+/// the MADT, excluding the BSP. For each one the BSP runs the full
+/// INIT–SIPI–SIPI handshake and blocks until the AP acknowledges.
+pub fn boot_aps(api: &mut Api, apic_ids: &[u8]) {
+    let apic_base = unsafe { rdmsr(IA32_APIC_BASE) & 0xffff_f000 } as usize;
+
+    BSP_API.store(api as *mut Api, Ordering::SeqCst);
+
+    let trampoline_page = stage_trampoline();
+    let vector = (trampoline_page >> 12) as u8;
+
+    for &target in apic_ids {
+        logln!("Bringing up AP with APIC ID {}", target);
+
+        // Each AP gets its own ring-0 interrupt stack and TSS so that faults
+        // taken during bringup do not clobber the BSP's state.
+        prepare_ap(trampoline_page);
+
+        let already_online = AP_ONLINE.load(Ordering::SeqCst);
+
+        unsafe {
+            // INIT IPI: delivery mode 0b101, assert, edge, physical destination.
+            send_ipi(apic_base, target, 0x0000_4500);
+            udelay(10_000);
+
+            // Two STARTUP IPIs, polling the delivery-status bit between sends.
+            for _ in 0..2 {
+                send_ipi(apic_base, target, 0x0000_4600 | vector as u32);
+                udelay(200);
+            }
+        }
+
+        // Spin-wait for this AP's acknowledgement before starting the next.
+        while AP_ONLINE.load(Ordering::SeqCst) <= already_online {
+            core::hint::spin_loop();
+        }
+    }
+}
+
+/// Copy the trampoline blob to a low physical frame and return its physical
+/// base. The frame number must fit in a byte so it can serve as the SIPI
+/// vector, which `allocate`'s low-memory pool guarantees.
+fn stage_trampoline() -> usize {
+    let frame = PHYSICAL_FRAME_ALLOCATOR
+        .lock()
+        .allocate()
+        .expect("failed to allocate AP trampoline frame");
+    let base = frame.phys_base();
+    assert!(
+        (base >> 12) <= u8::MAX as usize,
+        "AP trampoline page number must fit in the SIPI vector byte"
+    );
+    unsafe {
+        core::ptr::copy_nonoverlapping(addr_of!(trampoline_start), base as *mut u8, trampoline_len());
+    }
+    base
+}
+
+/// Size of the trampoline blob in bytes, used both to copy it and to locate the
+/// argument cells the BSP patches at its tail.
+fn trampoline_len() -> usize {
+    unsafe { addr_of!(trampoline_end) as usize - addr_of!(trampoline_start) as usize }
+}
+
+/// Allocate the per-core ring-0 stack/TSS and patch the trampoline argument
+/// cells so the AP climbs into `ap_entry` on its own stack.
+fn prepare_ap(trampoline_page: usize) {
+    let stack = PHYSICAL_FRAME_ALLOCATOR
+        .lock()
+        .allocate()
+        .expect("failed to allocate AP ring-0 stack");
+    let stack_top = stack.phys_base() + 0x1000;
+
+    // A fresh per-CPU TSS (carrying the ring-0 interrupt stack) and the GDT that
+    // references it. Both outlive the AP, so they are leaked into 'static storage
+    // and published for `ap_entry` to install.
+    let tss: &'static Tss = Box::leak(Box::new(Tss::new(stack_top as u64)));
+    let gdt: &'static Gdt = Box::leak(Box::new(Gdt::new(tss)));
+    AP_GDT.store(gdt as *const Gdt as *mut Gdt, Ordering::SeqCst);
+
+    let args = ApTrampolineArgs {
+        pml4: read_cr3(),
+        stack_top: stack_top as u64,
+        entry: ap_entry as usize as u64,
+    };
+
+    unsafe {
+        // The three argument cells are the last 24 bytes of the trampoline blob;
+        // the trampoline reads them at that blob-relative offset.
+        let tail = (trampoline_page + trampoline_len()
+            - core::mem::size_of::<ApTrampolineArgs>()) as *mut ApTrampolineArgs;
+        write_volatile(tail, args);
+    }
+}
+
+/// Write the destination and command dwords of the ICR, kicking off an IPI.
+unsafe fn send_ipi(apic_base: usize, dest: u8, command: u32) {
+    write_icr_high(apic_base, (dest as u32) << 24);
+    write_icr_low(apic_base, command);
+    // Wait for the local APIC to accept the IPI before returning.
+    while read_icr_low(apic_base) & ICR_DELIVERY_PENDING != 0 {
+        core::hint::spin_loop();
+    }
+}
+
+unsafe fn write_icr_low(apic_base: usize, value: u32) {
+    write_volatile((apic_base + ICR_LOW) as *mut u32, value);
+}
+
+unsafe fn write_icr_high(apic_base: usize, value: u32) {
+    write_volatile((apic_base + ICR_HIGH) as *mut u32, value);
+}
+
+unsafe fn read_icr_low(apic_base: usize) -> u32 {
+    core::ptr::read_volatile((apic_base + ICR_LOW) as *const u32)
+}
+
+/// Entry point the trampoline jumps into once the AP is in long mode.
+///
+/// Installs this core's freshly staged per-CPU GDT/TSS and the shared IDT, then
+/// calls back into `Api::init_ap` for per-core finalization before parking the
+/// core in the scheduler.
+extern "C" fn ap_entry() -> ! {
+    // Install the per-CPU GDT/TSS staged by `prepare_ap` for this core.
+    let gdt = unsafe { &*AP_GDT.load(Ordering::SeqCst) };
+    gdt.load();
+    Gdt::reload_segment_regs();
+    Gdt::load_tss();
+    BSP_IDT.lock().load();
+
+    // Per-core finalization lives in `Api::init_ap`; the BSP published its `Api`
+    // before the SIPIs and brings APs up one at a time, so this borrow is sound.
+    let api = unsafe { &mut *BSP_API.load(Ordering::SeqCst) };
+    crate::arch::Api::init_ap(api);
+
+    AP_ONLINE.fetch_add(1, Ordering::SeqCst);
+
+    // The scheduler is not online yet; park until it claims this core.
+    loop {
+        unsafe { core::arch::asm!("hlt") };
+    }
+}
+
+unsafe fn rdmsr(msr: u32) -> u64 {
+    let (hi, lo): (u32, u32);
+    core::arch::asm!("rdmsr", in("ecx") msr, out("eax") lo, out("edx") hi);
+    ((hi as u64) << 32) | lo as u64
+}
+
+fn read_cr3() -> u64 {
+    let value: u64;
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) value) };
+    value
+}
+
+/// Busy-wait for approximately `micros` microseconds.
+///
+/// This is an **uncalibrated** placeholder: the spin count is a fixed multiple
+/// of `micros` with no relation to the actual core frequency, so the real delay
+/// varies with the host. Replace it with an APIC/PIT-calibrated timebase once
+/// one is exported.
+fn udelay(micros: u64) {
+    for _ in 0..micros.saturating_mul(1000) {
+        core::hint::spin_loop();
+    }
+}
@@ -0,0 +1,72 @@
+//! # In-Kernel Test Harness
+//! A small harness that replaces eyeballing serial logs with a pass/fail signal
+//! a CI runner can observe. Each self-test is a [`TestCase`] that reports its
+//! outcome over the [`SerialPort`] and, when the whole suite finishes, the VM is
+//! terminated through the QEMU `isa-debug-exit` device with a distinct status.
+
+use core::fmt::Write;
+
+use serial::{ComPort, SerialPort};
+
+use crate::arch::x86_64::cpu::asm_outb;
+use crate::arch::x86_64::serial;
+
+/// I/O port exposed by QEMU's `isa-debug-exit` device.
+const ISA_DEBUG_EXIT_PORT: u16 = 0xf4;
+
+/// Exit status written to the debug-exit port. QEMU reports `(code << 1) | 1`,
+/// so these values stay distinct from a normal `0` shutdown.
+#[derive(Clone, Copy)]
+#[repr(u32)]
+pub enum ExitCode {
+    Success = 0x10,
+    Failed = 0x11,
+}
+
+/// Terminate the VM with `code` via the QEMU debug-exit device. Never returns
+/// under QEMU; loops halted as a fallback on real hardware.
+pub fn exit_qemu(code: ExitCode) -> ! {
+    asm_outb(ISA_DEBUG_EXIT_PORT, code as u8);
+    loop {
+        unsafe { core::arch::asm!("hlt") };
+    }
+}
+
+/// A single registered self-test.
+pub trait TestCase {
+    /// Human-readable name printed alongside the outcome.
+    fn name(&self) -> &'static str;
+    /// Run the test; panic (or return) to signal failure/success.
+    fn run(&self);
+}
+
+/// Any zero-argument function paired with a name can serve as a test case.
+pub struct NamedTest {
+    pub name: &'static str,
+    pub test: fn(),
+}
+
+impl TestCase for NamedTest {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+    fn run(&self) {
+        (self.test)()
+    }
+}
+
+/// Run every registered test case, reporting each outcome over COM1, then exit
+/// QEMU with [`ExitCode::Success`]. A panicking test is expected to route
+/// through the panic handler, which reports the failure and exits with
+/// [`ExitCode::Failed`].
+pub fn run_tests(tests: &[&dyn TestCase]) -> ! {
+    let mut serial = SerialPort::try_new(ComPort::COM1).unwrap();
+    let _ = writeln!(serial, "running {} test(s)", tests.len());
+    for test in tests {
+        let _ = write!(serial, "test {} ... ", test.name());
+        test.run();
+        let _ = writeln!(serial, "ok");
+    }
+    let _ = writeln!(serial, "all tests passed");
+    exit_qemu(ExitCode::Success);
+}
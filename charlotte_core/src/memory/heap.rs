@@ -0,0 +1,209 @@
+//! # Kernel Heap
+//! A `#[global_allocator]` that unblocks `Box`/`Vec` and other dynamic
+//! structures in arch and driver code. The heap reserves a fixed virtual
+//! region and backs it with frames from the physical frame allocator on
+//! demand through [`crate::memory::vmm`], handing those frames to a
+//! linked-list free allocator guarded by a [`SpinMutex`].
+
+use core::alloc::{GlobalAlloc, Layout};
+use core::mem::{align_of, size_of};
+use core::ptr::null_mut;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use spin::mutex::spin::SpinMutex;
+
+use crate::memory::vmm::{self, WRITABLE};
+
+/// Base of the reserved virtual heap region (high half, below the MMIO window).
+const HEAP_BASE: usize = 0xffff_fe00_0000_0000;
+/// Maximum heap size (256 MiB of virtual space); frames are mapped lazily.
+const HEAP_MAX: usize = 0x1000_0000;
+/// Minimum number of frames mapped in each growth step; a larger request grows
+/// the heap by as many pages as it needs.
+const GROW_FRAMES: usize = 16;
+
+const PAGE_SIZE: usize = 0x1000;
+
+#[global_allocator]
+static ALLOCATOR: LockedHeap = LockedHeap::new();
+
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+/// Initialize the global heap. Idempotent: safe to call more than once during
+/// bring-up. Maps an initial span of frames and seeds the free list with it.
+pub fn init_heap() {
+    if INITIALIZED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let size = GROW_FRAMES * PAGE_SIZE;
+    map_region(HEAP_BASE, size);
+    unsafe { ALLOCATOR.0.lock().init(HEAP_BASE, size) };
+}
+
+/// A free-list node living at the head of a free block.
+struct FreeNode {
+    size: usize,
+    next: Option<&'static mut FreeNode>,
+}
+
+/// Linked-list heap: a sorted free list of coalesced blocks plus the top of the
+/// currently mapped region so it can grow into more frames on demand.
+struct Heap {
+    head: FreeNode,
+    mapped_top: usize,
+}
+
+impl Heap {
+    const fn empty() -> Self {
+        Heap {
+            head: FreeNode {
+                size: 0,
+                next: None,
+            },
+            mapped_top: HEAP_BASE,
+        }
+    }
+
+    unsafe fn init(&mut self, base: usize, size: usize) {
+        self.mapped_top = base + size;
+        self.push_free(base, size);
+    }
+
+    /// Minimum block size / alignment so a freed block can always hold a node.
+    fn block_layout(layout: Layout) -> (usize, usize) {
+        let align = layout.align().max(align_of::<FreeNode>());
+        let size = layout.size().max(size_of::<FreeNode>());
+        (align_up(size, align), align)
+    }
+
+    unsafe fn alloc(&mut self, layout: Layout) -> *mut u8 {
+        let (size, align) = Self::block_layout(layout);
+        let mut current = &mut self.head;
+        while let Some(ref mut region) = current.next {
+            let start = align_up(region_addr(region), align);
+            let end = start + size;
+            if end <= region_addr(region) + region.size {
+                // Block fits; splice it out, returning any trailing remainder.
+                let next = region.next.take();
+                let region_start = region_addr(region);
+                let region_size = region.size;
+                current.next = next;
+                // Only split off a remainder large enough to hold a free node;
+                // a smaller gap (from alignment padding or a tight fit) is left
+                // off the free list rather than written over with a 16-byte
+                // node, which would corrupt the adjacent block. `dealloc` frees
+                // only the block itself, so such a gap is not reclaimed.
+                if start - region_start >= size_of::<FreeNode>() {
+                    self.push_free(region_start, start - region_start);
+                }
+                let tail = region_start + region_size;
+                if tail - end >= size_of::<FreeNode>() {
+                    self.push_free(end, tail - end);
+                }
+                return start as *mut u8;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+        // Nothing fit; grow the mapped region and retry. A retry that still
+        // finds no fit grows again, until the region would exceed `HEAP_MAX`.
+        if self.grow(size) {
+            return self.alloc(layout);
+        }
+        null_mut()
+    }
+
+    unsafe fn dealloc(&mut self, ptr: *mut u8, layout: Layout) {
+        let (size, _) = Self::block_layout(layout);
+        self.push_free(ptr as usize, size);
+    }
+
+    /// Insert a free block, keeping the list address-sorted and coalescing with
+    /// neighbours.
+    unsafe fn push_free(&mut self, addr: usize, size: usize) {
+        let mut current = &mut self.head;
+        while let Some(ref region) = current.next {
+            if region_addr(region) > addr {
+                break;
+            }
+            current = current.next.as_mut().unwrap();
+        }
+        let node = addr as *mut FreeNode;
+        node.write(FreeNode {
+            size,
+            next: current.next.take(),
+        });
+        current.next = Some(&mut *node);
+        Self::coalesce(current);
+    }
+
+    /// Merge adjacent blocks from `from` onwards. `from` is the block preceding
+    /// a newly inserted one (or the zero-sized list head, which never touches a
+    /// heap block), so the new block joins both neighbours.
+    unsafe fn coalesce(mut from: &mut FreeNode) {
+        loop {
+            let from_end = region_addr(from) + from.size;
+            match from.next {
+                Some(ref next) if from_end == region_addr(next) => {
+                    let next = from.next.take().unwrap();
+                    from.size += next.size;
+                    from.next = next.next.take();
+                }
+                Some(_) => from = from.next.as_mut().unwrap(),
+                None => break,
+            }
+        }
+    }
+
+    /// Map more frames at the top of the heap and add them to the free list.
+    unsafe fn grow(&mut self, at_least: usize) -> bool {
+        let step = align_up(at_least.max(GROW_FRAMES * PAGE_SIZE), PAGE_SIZE);
+        if self.mapped_top + step > HEAP_BASE + HEAP_MAX {
+            return false;
+        }
+        map_region(self.mapped_top, step);
+        let base = self.mapped_top;
+        self.mapped_top += step;
+        self.push_free(base, step);
+        true
+    }
+}
+
+/// `GlobalAlloc` wrapper holding the spin-guarded heap.
+struct LockedHeap(SpinMutex<Heap>);
+
+impl LockedHeap {
+    const fn new() -> Self {
+        LockedHeap(SpinMutex::new(Heap::empty()))
+    }
+}
+
+unsafe impl GlobalAlloc for LockedHeap {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.lock().alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.lock().dealloc(ptr, layout)
+    }
+}
+
+/// Back `[vaddr, vaddr + size)` with freshly allocated, writable frames.
+fn map_region(vaddr: usize, size: usize) {
+    let mut space = vmm::AddressSpace::current();
+    let pages = size / PAGE_SIZE;
+    for page in 0..pages {
+        let frame = crate::memory::pmm::PHYSICAL_FRAME_ALLOCATOR
+            .lock()
+            .allocate()
+            .expect("out of frames while growing the kernel heap");
+        space.map(vaddr + page * PAGE_SIZE, frame.phys_base(), WRITABLE);
+    }
+}
+
+fn region_addr(node: &FreeNode) -> usize {
+    node as *const FreeNode as usize
+}
+
+fn align_up(value: usize, align: usize) -> usize {
+    (value + align - 1) & !(align - 1)
+}
@@ -0,0 +1,175 @@
+//! # Virtual Memory Manager
+//! A minimal x86_64 4-level (PML4) paging subsystem layered on top of the
+//! physical frame allocator. An [`AddressSpace`] owns a PML4 frame and can
+//! map, unmap, and translate virtual addresses, allocating intermediate page
+//! tables from the PMM on demand. [`map_mmio`] carves device register windows
+//! out of a reserved high-half region with caching disabled so that drivers
+//! such as `Apic` and `IoApic` can remap their registers anywhere in the
+//! virtual address space instead of assuming identity-mapped physical memory.
+//!
+//! Note that the table walks ([`AddressSpace::walk`]/`walk_create`) still
+//! dereference page-table frames through their physical address, which assumes
+//! physical memory is identity-mapped (as it is under the bootloader's initial
+//! page tables). Lifting that assumption — walking tables through a dedicated
+//! direct-map or MMIO window — is left for when the kernel installs its own
+//! address space.
+
+use spin::mutex::spin::SpinMutex;
+
+use crate::memory::pmm::PHYSICAL_FRAME_ALLOCATOR;
+
+/// Present bit.
+pub const PRESENT: u64 = 1 << 0;
+/// Writable bit.
+pub const WRITABLE: u64 = 1 << 1;
+/// Page-level write-through.
+pub const PWT: u64 = 1 << 3;
+/// Page-level cache disable.
+pub const PCD: u64 = 1 << 4;
+/// No-execute bit (honoured once EFER.NXE is set).
+pub const NO_EXECUTE: u64 = 1 << 63;
+
+const PAGE_SIZE: usize = 0x1000;
+const ADDR_MASK: u64 = 0x000f_ffff_ffff_f000;
+
+/// Base of the reserved high-half window from which device MMIO is allocated.
+const MMIO_WINDOW_BASE: usize = 0xffff_ff00_0000_0000;
+/// Size of the MMIO window (1 GiB of virtual space).
+const MMIO_WINDOW_SIZE: usize = 0x4000_0000;
+
+/// Bump allocator for the MMIO window. Device windows are never reclaimed, so
+/// a monotonically increasing cursor is sufficient.
+static MMIO_CURSOR: SpinMutex<usize> = SpinMutex::new(MMIO_WINDOW_BASE);
+
+/// An x86_64 virtual address space, identified by the physical frame holding
+/// its PML4.
+pub struct AddressSpace {
+    pml4: usize,
+}
+
+impl AddressSpace {
+    /// Wrap the PML4 frame currently installed in CR3.
+    pub fn current() -> Self {
+        AddressSpace {
+            pml4: (read_cr3() & ADDR_MASK) as usize,
+        }
+    }
+
+    /// Map `vaddr` to `paddr` with `flags`, allocating any missing intermediate
+    /// tables from the PMM. `flags` is OR-ed with the present bit.
+    pub fn map(&mut self, vaddr: usize, paddr: usize, flags: u64) {
+        let entry = self.walk_create(vaddr);
+        unsafe { *entry = (paddr as u64 & ADDR_MASK) | flags | PRESENT };
+    }
+
+    /// Remove the mapping for `vaddr` if present and flush its TLB entry.
+    pub fn unmap(&mut self, vaddr: usize) {
+        if let Some(entry) = self.walk(vaddr) {
+            unsafe { *entry = 0 };
+            invlpg(vaddr);
+        }
+    }
+
+    /// Resolve `vaddr` to the physical address it maps to, or `None` if unmapped.
+    pub fn translate(&self, vaddr: usize) -> Option<usize> {
+        let entry = self.walk(vaddr)?;
+        let value = unsafe { *entry };
+        if value & PRESENT == 0 {
+            None
+        } else {
+            Some(((value & ADDR_MASK) as usize) | (vaddr & (PAGE_SIZE - 1)))
+        }
+    }
+
+    /// Walk the four levels, allocating a fresh table frame wherever one is
+    /// missing, and return a pointer to the leaf PTE.
+    fn walk_create(&mut self, vaddr: usize) -> *mut u64 {
+        let mut table = self.pml4;
+        for level in (1..4).rev() {
+            let entry = unsafe { table_entry(table, index(vaddr, level)) };
+            let value = unsafe { *entry };
+            table = if value & PRESENT != 0 {
+                (value & ADDR_MASK) as usize
+            } else {
+                let frame = PHYSICAL_FRAME_ALLOCATOR
+                    .lock()
+                    .allocate()
+                    .expect("out of frames while building page tables");
+                let base = frame.phys_base();
+                unsafe { zero_table(base) };
+                unsafe { *entry = (base as u64 & ADDR_MASK) | PRESENT | WRITABLE };
+                base
+            };
+        }
+        unsafe { table_entry(table, index(vaddr, 0)) }
+    }
+
+    /// Walk the four levels without allocating; return the leaf PTE pointer or
+    /// `None` if any intermediate table is absent.
+    fn walk(&self, vaddr: usize) -> Option<*mut u64> {
+        let mut table = self.pml4;
+        for level in (1..4).rev() {
+            let entry = unsafe { table_entry(table, index(vaddr, level)) };
+            let value = unsafe { *entry };
+            if value & PRESENT == 0 {
+                return None;
+            }
+            table = (value & ADDR_MASK) as usize;
+        }
+        Some(unsafe { table_entry(table, index(vaddr, 0)) })
+    }
+}
+
+/// Map `len` bytes of device MMIO starting at physical `paddr` into the reserved
+/// high-half window with caching disabled (PCD/PWT) and return the virtual base.
+///
+/// Offsets within the page are preserved so callers can pass an unaligned
+/// register base and use the returned pointer directly.
+pub fn map_mmio(paddr: usize, len: usize) -> usize {
+    let offset = paddr & (PAGE_SIZE - 1);
+    let first = paddr - offset;
+    let pages = (offset + len + PAGE_SIZE - 1) / PAGE_SIZE;
+
+    let mut cursor = MMIO_CURSOR.lock();
+    let vbase = *cursor;
+    assert!(
+        vbase + pages * PAGE_SIZE <= MMIO_WINDOW_BASE + MMIO_WINDOW_SIZE,
+        "MMIO window exhausted"
+    );
+    *cursor += pages * PAGE_SIZE;
+    drop(cursor);
+
+    let mut space = AddressSpace::current();
+    let flags = WRITABLE | PCD | PWT | NO_EXECUTE;
+    for page in 0..pages {
+        space.map(
+            vbase + page * PAGE_SIZE,
+            first + page * PAGE_SIZE,
+            flags,
+        );
+    }
+    vbase + offset
+}
+
+fn index(vaddr: usize, level: usize) -> usize {
+    (vaddr >> (12 + 9 * level)) & 0x1ff
+}
+
+unsafe fn table_entry(table: usize, index: usize) -> *mut u64 {
+    (table as *mut u64).add(index)
+}
+
+unsafe fn zero_table(base: usize) {
+    core::ptr::write_bytes(base as *mut u8, 0, PAGE_SIZE);
+}
+
+fn read_cr3() -> u64 {
+    let value: u64;
+    unsafe { core::arch::asm!("mov {}, cr3", out(reg) value) };
+    value
+}
+
+/// Invalidate a single TLB entry.
+fn invlpg(vaddr: usize) {
+    unsafe { core::arch::asm!("invlpg [{}]", in(reg) vaddr, options(nostack, preserves_flags)) };
+}